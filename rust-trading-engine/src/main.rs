@@ -4,22 +4,22 @@ use matching_engine::engine::{TradingPair, MatchingEngine};
 use rust_decimal_macros::dec;
 
 fn main() {
-    let buy_order_from_alice = Order::new(BidOrAsk::Bid, 5.5);
-    let buy_order_from_bob = Order::new(BidOrAsk::Bid, 2.45);
+    let buy_order_from_alice = Order::new(BidOrAsk::Bid, dec!(5.5), 1);
+    let buy_order_from_bob = Order::new(BidOrAsk::Bid, dec!(2.45), 2);
 
-    let mut orderbook = OrderBook:: new();
-    orderbook.add_limit_order(dec!(4.4), buy_order_from_alice);
-    orderbook.add_limit_order(dec!(4.4), buy_order_from_bob);
- 
-    let sell_order = Order::new(BidOrAsk::Ask, 6.5);
-    orderbook.add_limit_order(dec!(20.0), sell_order);
+    let mut orderbook = OrderBook::new(dec!(0.01), dec!(0.01), dec!(0)).unwrap();
+    orderbook.add_limit_order(dec!(4.4), buy_order_from_alice).unwrap();
+    orderbook.add_limit_order(dec!(4.4), buy_order_from_bob).unwrap();
+
+    let sell_order = Order::new(BidOrAsk::Ask, dec!(6.5), 3);
+    orderbook.add_limit_order(dec!(20.0), sell_order).unwrap();
     // println!("{:?}", orderbook);
 
     let mut engine = MatchingEngine::new();
     let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
-    engine.add_new_market(pair.clone());
+    engine.add_new_market(pair.clone(), dec!(0.01), dec!(0.01), dec!(0)).unwrap();
 
-    let buy_order = Order::new(BidOrAsk::Bid, 6.5);
+    let buy_order = Order::new(BidOrAsk::Bid, dec!(6.5), 4);
     // let res = engine.place_limit_order(pair, 10.000, buy_order);
     // match res {
     //     Ok(()) => {}
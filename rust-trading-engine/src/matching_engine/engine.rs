@@ -1,6 +1,19 @@
 use std::{collections::HashMap, fmt::format};
-use rust_decimal::Decimal;
-use super::orderbook::{OrderBook, Order};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use super::orderbook::{OrderBook, Order, OrderType, Fill, BidOrAsk, OrderId, OwnerId};
+
+// bounds how many stop/stop-limit orders can sit untriggered per market at once
+const MAX_PENDING_STOPS_PER_MARKET: usize = 50;
+
+// a maker/taker rate that applies once a taker's cumulative traded notional
+// reaches `threshold_notional`; rates are in basis points (1 bps = 1/10_000)
+#[derive(Debug, Clone, Copy)]
+pub struct FeeTier {
+    pub threshold_notional: Decimal,
+    pub maker_bps: Decimal,
+    pub taker_bps: Decimal,
+}
 
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
@@ -24,33 +37,323 @@ impl TradingPair {
 
 pub struct MatchingEngine {
 	orderbooks: HashMap<TradingPair, OrderBook>,
+	// stop/stop-limit orders waiting for the last trade price to cross their trigger
+	pending_stops: HashMap<TradingPair, Vec<Order>>,
+	last_price: HashMap<TradingPair, Decimal>,
+	// maker/taker rate tiers, in any order (picked by threshold); empty means no fees are charged
+	fee_schedule: Vec<FeeTier>,
+	// each owner's cumulative traded notional as a taker, used to pick their fee tier
+	taker_notional: HashMap<OwnerId, Decimal>,
+	accumulated_fees: HashMap<TradingPair, Decimal>,
 }
 
 impl MatchingEngine {
 	pub fn new() -> MatchingEngine {
 		MatchingEngine{
 			orderbooks: HashMap::new(),
+			pending_stops: HashMap::new(),
+			last_price: HashMap::new(),
+			fee_schedule: Vec::new(),
+			taker_notional: HashMap::new(),
+			accumulated_fees: HashMap::new(),
 		}
 	}
 
-	pub fn add_new_market(&mut self, pair: TradingPair) {
-		self.orderbooks.insert(pair.clone(), OrderBook::new());
+	pub fn with_fee_schedule(mut self, fee_schedule: Vec<FeeTier>) -> MatchingEngine {
+		self.fee_schedule = fee_schedule;
+		self
+	}
+
+	pub fn add_new_market(&mut self, pair: TradingPair, tick_size: Decimal, lot_size: Decimal, min_size: Decimal) -> Result<(), String> {
+		let orderbook = OrderBook::new(tick_size, lot_size, min_size)?;
+
+		self.orderbooks.insert(pair.clone(), orderbook);
+		self.pending_stops.insert(pair.clone(), Vec::new());
+		self.accumulated_fees.insert(pair.clone(), Decimal::ZERO);
 		println!("opening a new orderbook for market {:?}", pair.to_string());
+		Ok(())
+	}
+
+	// total maker + taker fees collected so far on `pair`
+	pub fn accumulated_fees(&self, pair: &TradingPair) -> Decimal {
+		self.accumulated_fees.get(pair).copied().unwrap_or(Decimal::ZERO)
+	}
+
+	// the tier that applies to a taker who has already traded `cumulative_notional`:
+	// the richest tier whose threshold they've met, or a zero-fee tier if none has
+	fn fee_tier_for(&self, cumulative_notional: Decimal) -> FeeTier {
+		self.fee_schedule
+			.iter()
+			.filter(|tier| tier.threshold_notional <= cumulative_notional)
+			.max_by_key(|tier| tier.threshold_notional)
+			.copied()
+			.unwrap_or(FeeTier { threshold_notional: Decimal::ZERO, maker_bps: Decimal::ZERO, taker_bps: Decimal::ZERO })
+	}
+
+	// fills in each fill's maker/taker fee from the schedule, tracking the taker's
+	// cumulative notional and this market's accumulated fees as it goes
+	fn apply_fees(&mut self, pair: &TradingPair, fills: Vec<Fill>) -> Vec<Fill> {
+		fills
+			.into_iter()
+			.map(|mut fill| {
+				let notional = fill.price * fill.size;
+
+				let taker_cumulative = self.taker_notional.get(&fill.taker_owner).copied().unwrap_or(Decimal::ZERO);
+				let tier = self.fee_tier_for(taker_cumulative);
+
+				fill.maker_fee = notional * tier.maker_bps / dec!(10000);
+				fill.taker_fee = notional * tier.taker_bps / dec!(10000);
+
+				*self.taker_notional.entry(fill.taker_owner).or_insert(Decimal::ZERO) += notional;
+				*self.accumulated_fees.entry(pair.clone()).or_insert(Decimal::ZERO) += fill.maker_fee + fill.taker_fee;
+
+				fill
+			})
+			.collect()
+	}
+
+	pub fn place_limit_order(&mut self, pair: TradingPair, price: Decimal, order: Order) -> Result<Vec<Fill>, String>{
+		let mut fills = match self.orderbooks.get_mut(&pair) {
+			Some(orderbook) => orderbook.add_limit_order(price, order)?,
+			None => {
+				return Err(format!(
+					"the orderbook for the given trading pair ({}) does not exist",
+					pair.to_string()
+				));
+			}
+		};
+		println!("placed limit order at price level {}", price);
+
+		let triggered_fills = self.activate_pending_stops(&pair, &fills)?;
+		fills.extend(triggered_fills);
+		Ok(self.apply_fees(&pair, fills))
+	}
+
+	// queues a stop/stop-limit order to activate once the market's last trade price
+	// crosses its trigger; rejected once the market already holds the maximum
+	pub fn place_stop_order(&mut self, pair: TradingPair, order: Order) -> Result<(), String> {
+		let orderbook = match self.orderbooks.get(&pair) {
+			Some(orderbook) => orderbook,
+			None => {
+				return Err(format!(
+					"the orderbook for the given trading pair ({}) does not exist",
+					pair.to_string()
+				));
+			}
+		};
+
+		// validate now against the market's tick/lot/min-size so activation never fails
+		// those checks later and silently drops the order; a plain stop has no limit price to
+		// check against the tick size, so validate it as if resting at price zero,
+		// which is always a multiple of the tick size
+		let validation_price = match order.order_type() {
+			OrderType::StopLimit { limit, .. } => limit,
+			_ => Decimal::ZERO,
+		};
+		orderbook.validate_limit_order(validation_price, &order)?;
+
+		let pending = self.pending_stops.entry(pair.clone()).or_insert_with(Vec::new);
+		if pending.len() >= MAX_PENDING_STOPS_PER_MARKET {
+			return Err(format!(
+				"market {} already has the maximum of {} pending stop orders",
+				pair.to_string(), MAX_PENDING_STOPS_PER_MARKET
+			));
+		}
+
+		pending.push(order);
+		Ok(())
+	}
+
+	// records the latest trade price for `pair` and activates any pending stop orders
+	// whose trigger it now crosses, routing each straight back into the matching path.
+	// orders are validated at `place_stop_order` time, but an activated order can still fail to
+	// match (e.g. a self-trade abort) and that error is propagated rather than dropped.
+	// activating one stop can itself move the last trade price far enough to trigger
+	// another, so this sweeps in rounds until a round activates nothing new
+	fn activate_pending_stops(&mut self, pair: &TradingPair, new_fills: &[Fill]) -> Result<Vec<Fill>, String> {
+		if let Some(last_fill) = new_fills.last() {
+			self.last_price.insert(pair.clone(), last_fill.price);
+		}
+
+		let mut triggered_fills = Vec::new();
+
+		loop {
+			let last_price = match self.last_price.get(pair).copied() {
+				Some(last_price) => last_price,
+				None => break,
+			};
+
+			let pending = match self.pending_stops.get_mut(pair) {
+				Some(pending) => std::mem::take(pending),
+				None => break,
+			};
+
+			let (ready, still_pending): (Vec<Order>, Vec<Order>) = pending
+				.into_iter()
+				.partition(|order| order.is_triggered(last_price));
+
+			if let Some(slot) = self.pending_stops.get_mut(pair) {
+				*slot = still_pending;
+			}
+
+			if ready.is_empty() {
+				break;
+			}
+
+			for order in ready {
+				let (mut activated, limit_price) = order.activate();
+
+				if let Some(orderbook) = self.orderbooks.get_mut(pair) {
+					let fills = match limit_price {
+						Some(limit_price) => orderbook.add_limit_order(limit_price, activated)?,
+						None => orderbook.fill_market_order(&mut activated),
+					};
+
+					if let Some(last_fill) = fills.last() {
+						self.last_price.insert(pair.clone(), last_fill.price);
+					}
+
+					triggered_fills.extend(fills);
+				}
+			}
+		}
+
+		Ok(triggered_fills)
+	}
+
+	pub fn cancel_order(&mut self, pair: TradingPair, id: OrderId) -> Result<bool, String> {
+		match self.orderbooks.get_mut(&pair) {
+			Some(orderbook) => Ok(orderbook.cancel_order(id)),
+			None => {
+				Err(format!(
+					"the orderbook for the given trading pair ({}) does not exist",
+					pair.to_string()
+				))
+			}
+		}
 	}
 
-	pub fn place_limit_order(&mut self, pair: TradingPair, price: Decimal, order: Order) -> Result<(), String>{
-		match self.orderbooks.get_mut(&pair) { 
+	pub fn cancel_all_orders(&mut self, pair: TradingPair, side: BidOrAsk) -> Result<(), String> {
+		match self.orderbooks.get_mut(&pair) {
 			Some(orderbook) => {
-				orderbook.add_limit_order(price, order);
-				println!("placed limit order at price level {}", price);
+				orderbook.cancel_all_orders(side);
 				Ok(())
 			}
 			None => {
 				Err(format!(
-					"the orderbook for the given trading pair ({}) does not exist", 
+					"the orderbook for the given trading pair ({}) does not exist",
 					pair.to_string()
 				))
 			}
 		}
 	}
+}
+
+#[cfg(test)]
+pub mod tests {
+	use super::*;
+
+	fn new_engine() -> (MatchingEngine, TradingPair) {
+		let mut engine = MatchingEngine::new();
+		let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+		engine.add_new_market(pair.clone(), dec!(1), dec!(1), dec!(0)).unwrap();
+		(engine, pair)
+	}
+
+	#[test]
+	fn stop_order_triggers_and_routes_into_a_fill() {
+		let (mut engine, pair) = new_engine();
+
+		engine.place_stop_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(10), 1)
+			.with_order_type(OrderType::Stop { trigger: dec!(100) })).unwrap();
+
+		// a trade at 90 doesn't cross the trigger, so the stop stays pending
+		engine.place_limit_order(pair.clone(), dec!(90), Order::new(BidOrAsk::Ask, dec!(10), 2)).unwrap();
+		engine.place_limit_order(pair.clone(), dec!(90), Order::new(BidOrAsk::Bid, dec!(10), 3)).unwrap();
+
+		// a trade at 100 crosses the trigger and activates the stop as a market order,
+		// which fills against the 10 left resting at 100
+		engine.place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(20), 4)).unwrap();
+		let fills = engine.place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Bid, dec!(10), 5)).unwrap();
+
+		assert_eq!(fills.len(), 2);
+		assert_eq!(fills[0].size, dec!(10));
+		assert_eq!(fills[1].taker_owner, 1);
+		assert_eq!(fills[1].size, dec!(10));
+	}
+
+	#[test]
+	fn pending_stops_are_capped_per_market() {
+		let (mut engine, pair) = new_engine();
+
+		for owner in 0..MAX_PENDING_STOPS_PER_MARKET as u64 {
+			engine.place_stop_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(1), owner)
+				.with_order_type(OrderType::Stop { trigger: dec!(100) })).unwrap();
+		}
+
+		let result = engine.place_stop_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(1), 999)
+			.with_order_type(OrderType::Stop { trigger: dec!(100) }));
+
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn place_stop_order_rejects_a_stop_limit_whose_price_violates_the_tick_size() {
+		let (mut engine, pair) = new_engine();
+
+		let result = engine.place_stop_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(10), 1)
+			.with_order_type(OrderType::StopLimit { trigger: dec!(100), limit: dec!(100.5) }));
+
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn cascading_stop_triggers_all_fire_within_one_placement() {
+		let (mut engine, pair) = new_engine();
+
+		engine.place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(20), 10)).unwrap();
+		engine.place_limit_order(pair.clone(), dec!(110), Order::new(BidOrAsk::Ask, dec!(10), 11)).unwrap();
+
+		// stop2 only triggers once stop1's own activation pushes the last price up to 110
+		engine.place_stop_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(20), 1)
+			.with_order_type(OrderType::Stop { trigger: dec!(100) })).unwrap();
+		engine.place_stop_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(5), 2)
+			.with_order_type(OrderType::Stop { trigger: dec!(105) })).unwrap();
+
+		let fills = engine.place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Bid, dec!(5), 99)).unwrap();
+
+		// taker's own fill, stop1 sweeping the rest of 100 then spilling into 110,
+		// and stop2 only firing because that spill carried the last price past 105
+		assert_eq!(fills.len(), 4);
+		assert_eq!(fills.last().unwrap().maker_owner, 11);
+	}
+
+	#[test]
+	fn fee_tier_for_picks_the_richest_tier_the_taker_has_reached() {
+		let engine = MatchingEngine::new().with_fee_schedule(vec![
+			FeeTier { threshold_notional: Decimal::ZERO, maker_bps: dec!(10), taker_bps: dec!(20) },
+			FeeTier { threshold_notional: dec!(1000), maker_bps: dec!(5), taker_bps: dec!(10) },
+		]);
+
+		assert_eq!(engine.fee_tier_for(dec!(500)).taker_bps, dec!(20));
+		assert_eq!(engine.fee_tier_for(dec!(1000)).taker_bps, dec!(10));
+		assert_eq!(engine.fee_tier_for(dec!(5000)).taker_bps, dec!(10));
+	}
+
+	#[test]
+	fn apply_fees_charges_maker_and_taker_and_accumulates_per_market() {
+		let (mut engine, pair) = new_engine();
+		engine = engine.with_fee_schedule(vec![
+			FeeTier { threshold_notional: Decimal::ZERO, maker_bps: dec!(10), taker_bps: dec!(20) },
+		]);
+
+		engine.place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(10), 1)).unwrap();
+		let fills = engine.place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Bid, dec!(10), 2)).unwrap();
+
+		// notional = 100 * 10 = 1000; maker fee = 1000 * 10/10000 = 1, taker fee = 1000 * 20/10000 = 2
+		assert_eq!(fills.len(), 1);
+		assert_eq!(fills[0].maker_fee, dec!(1));
+		assert_eq!(fills[0].taker_fee, dec!(2));
+		assert_eq!(engine.accumulated_fees(&pair), dec!(3));
+	}
 }
\ No newline at end of file
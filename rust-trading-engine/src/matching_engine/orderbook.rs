@@ -1,59 +1,316 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use rust_decimal::prelude::*;
 
+pub type OrderId = u64;
+pub type OwnerId = u64;
 
-#[derive(Debug)]
+static NEXT_ORDER_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BidOrAsk {
     Bid,
     Ask,
 }
 
+// how an order is priced: orders routed into the book are `Limit`/`Market`, while
+// `Stop`/`StopLimit` sit out of the book until the last trade price crosses `trigger`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderType {
+    Market,
+    Limit,
+    Stop { trigger: Decimal },
+    StopLimit { trigger: Decimal, limit: Decimal },
+}
+
+// what an order wants to happen if it would otherwise match its own owner's resting order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelfTradeBehavior {
+    // cancel the smaller of the two orders and take the difference from the taker
+    DecrementTake,
+    // cancel the resting maker order and keep matching the taker against the book
+    CancelProvide,
+    // reject the whole incoming order instead of crossing it
+    AbortTransaction,
+}
+
+// a single execution produced while matching: the resting (maker) limit's price,
+// the size that changed hands, and which side was resting vs incoming. `maker_fee`
+// and `taker_fee` are left at zero here and filled in by the engine, which is the
+// only layer that knows the fee schedule and each owner's cumulative notional
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub price: Decimal,
+    pub size: Decimal,
+    pub maker_side: BidOrAsk,
+    pub taker_side: BidOrAsk,
+    pub maker_owner: OwnerId,
+    pub taker_owner: OwnerId,
+    pub maker_fee: Decimal,
+    pub taker_fee: Decimal,
+}
+
 #[derive(Debug)]
 pub struct OrderBook {
-    asks: HashMap<Decimal, Limit>,
-    bids: HashMap<Decimal, Limit>,
+    // BTreeMap keeps price levels sorted, so the best price is just the first/last
+    // entry instead of a re-sort on every lookup
+    asks: BTreeMap<Decimal, Limit>,
+    bids: BTreeMap<Decimal, Limit>,
+    // price index so a resting order can be located without scanning every limit
+    order_index: HashMap<OrderId, (BidOrAsk, Decimal)>,
+    tick_size: Decimal,
+    lot_size: Decimal,
+    min_size: Decimal,
 }
 
 impl OrderBook {
-    pub fn new() -> OrderBook {
-        OrderBook{
-            asks: HashMap::new(),
-            bids: HashMap::new(),
+    // tick_size/lot_size must be strictly positive: validate_limit_order divides by
+    // them, and a zero would panic on the very first order placed against this market
+    pub fn new(tick_size: Decimal, lot_size: Decimal, min_size: Decimal) -> Result<OrderBook, String> {
+        if tick_size <= Decimal::ZERO {
+            return Err(format!("tick size {} must be greater than zero", tick_size));
+        }
+
+        if lot_size <= Decimal::ZERO {
+            return Err(format!("lot size {} must be greater than zero", lot_size));
+        }
+
+        Ok(OrderBook{
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            order_index: HashMap::new(),
+            tick_size,
+            lot_size,
+            min_size,
+        })
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.best_ask()? - self.best_bid()?)
+    }
+
+    // rejects prices/sizes that don't line up with this market's tick/lot/min-size
+    pub(crate) fn validate_limit_order(&self, price: Decimal, order: &Order) -> Result<(), String> {
+        if price % self.tick_size != Decimal::ZERO {
+            return Err(format!("price {} is not a multiple of the tick size {}", price, self.tick_size));
+        }
+
+        if order.size % self.lot_size != Decimal::ZERO {
+            return Err(format!("size {} is not a multiple of the lot size {}", order.size, self.lot_size));
+        }
+
+        if order.size < self.min_size {
+            return Err(format!("size {} is below the minimum order size {}", order.size, self.min_size));
+        }
+
+        Ok(())
+    }
+
+    // removes a single resting order by id, dropping its limit if it empties out;
+    // returns whether an order with that id was found
+    pub fn cancel_order(&mut self, id: OrderId) -> bool {
+        match self.order_index.remove(&id) {
+            Some((side, price)) => {
+                let limits = match side {
+                    BidOrAsk::Bid => &mut self.bids,
+                    BidOrAsk::Ask => &mut self.asks,
+                };
+
+                match limits.get_mut(&price) {
+                    Some(limit) => {
+                        let removed = limit.remove_order(id);
+                        // a limit can still hold zero-size "ghost" orders that were
+                        // filled but never removed, so checking the vec's length
+                        // alone would leave a dead price level resting in the book
+                        if removed && limit.is_filled() {
+                            limits.remove(&price);
+                        }
+                        removed
+                    }
+                    None => false,
+                }
+            }
+            None => false,
+        }
+    }
+
+    // clears every resting order on one side of the book at once
+    pub fn cancel_all_orders(&mut self, side: BidOrAsk) {
+        let limits = match side {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
+        };
+
+        let ids: Vec<OrderId> = limits.values().flat_map(|limit| limit.orders.iter().map(|order| order.id)).collect();
+        limits.clear();
+
+        for id in ids {
+            self.order_index.remove(&id);
         }
     }
 
-    pub fn fill_market_order(&mut self, market_order: &mut Order) { // return a vec of matches
+    pub fn fill_market_order(&mut self, market_order: &mut Order) -> Vec<Fill> {
         let limits: Vec<&mut Limit> = match market_order.bid_or_ask {
             BidOrAsk::Bid => self.ask_limits(),
             BidOrAsk::Ask => self.bid_limits(),
         };
 
+        let mut fills = Vec::new();
+
         for limit_order in limits {
-            limit_order.fill_order(market_order);
+            fills.extend(limit_order.fill_order(market_order));
 
             if market_order.is_filled() {
                 break;
             }
         }
+
+        fills
     }
 
-    // BID (BUY ORDER) => ASKS limits => sorted cheapest price
+    // BID (BUY ORDER) => ASKS limits, cheapest price first (BTreeMap order, no sort needed)
     pub fn ask_limits(&mut self) -> Vec<&mut Limit> {
-        let mut limits = self.asks.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a, b| a.price.cmp(&b.price));
-
-        limits
+        self.asks.values_mut().collect()
     }
 
-    // ASK (SELL ORDER) => BID limits => sorted highest price
+    // ASK (SELL ORDER) => BID limits, highest price first (BTreeMap order, reversed)
     pub fn bid_limits(&mut self) -> Vec<&mut Limit> {
-        let mut limits = self.bids.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a, b| b.price.cmp(&a.price));
+        self.bids.values_mut().rev().collect()
+    }
+
+    // crosses the incoming order against the opposite side of the book and rests
+    // whatever remains unfilled at `price`; returns every fill produced by the cross
+    pub fn add_limit_order(&mut self, price: Decimal, mut order: Order) -> Result<Vec<Fill>, String> {
+        self.validate_limit_order(price, &order)?;
+
+        let fills = match order.bid_or_ask {
+            BidOrAsk::Bid => self.match_bid(&mut order, price)?,
+            BidOrAsk::Ask => self.match_ask(&mut order, price)?,
+        };
+
+        if !order.is_filled() {
+            self.rest_limit_order(price, order);
+        }
+
+        Ok(fills)
+    }
+
+    // true if aborting this order is required: it would otherwise self-trade and the
+    // taker asked for AbortTransaction; checked up front so a reject never leaves the
+    // book partially matched. Simulates the same FIFO walk `fill_limit_order` will
+    // actually perform, tracking how much of the taker's size is still live, so a
+    // same-owner resting order the taker would never reach (because other owners'
+    // liquidity fully satisfies it first) doesn't cause a false abort. Takes a
+    // read-only pass over the crossable limits so the check never needs to
+    // materialize a Vec of its own.
+    fn would_abort_on_self_trade<'a>(order: &Order, crossable: impl Iterator<Item = &'a Limit>) -> Option<String> {
+        if order.self_trade_behavior != SelfTradeBehavior::AbortTransaction {
+            return None;
+        }
+
+        let mut remaining = order.size;
+
+        for limit in crossable {
+            if remaining.is_zero() {
+                break;
+            }
+
+            for resting in limit.orders.iter().filter(|resting| !resting.is_filled()) {
+                if remaining.is_zero() {
+                    break;
+                }
+
+                if resting.owner == order.owner {
+                    return Some(format!(
+                        "order from owner {} aborted: would self-trade against its own resting order at price {}",
+                        order.owner, limit.price
+                    ));
+                }
+
+                remaining -= remaining.min(resting.size);
+            }
+        }
+
+        None
+    }
+
+    // walk the asks cheapest-first while their price is at or below `price`
+    fn match_bid(&mut self, order: &mut Order, price: Decimal) -> Result<Vec<Fill>, String> {
+        let mut fills = Vec::new();
+        let mut drained = Vec::new();
+
+        let crossable = self.asks.values().take_while(|limit| limit.price <= price);
+        if let Some(err) = Self::would_abort_on_self_trade(order, crossable) {
+            return Err(err);
+        }
+
+        for limit in self.asks.values_mut().take_while(|limit| limit.price <= price) {
+            if order.is_filled() {
+                break;
+            }
+
+            fills.extend(limit.fill_limit_order(order)?);
+
+            if limit.is_filled() {
+                drained.push(limit.price);
+            }
+        }
+
+        for price in drained {
+            if let Some(limit) = self.asks.remove(&price) {
+                for order in &limit.orders {
+                    self.order_index.remove(&order.id);
+                }
+            }
+        }
+
+        Ok(fills)
+    }
+
+    // walk the bids priciest-first while their price is at or above `price`
+    fn match_ask(&mut self, order: &mut Order, price: Decimal) -> Result<Vec<Fill>, String> {
+        let mut fills = Vec::new();
+        let mut drained = Vec::new();
 
-        limits
+        let crossable = self.bids.values().rev().take_while(|limit| limit.price >= price);
+        if let Some(err) = Self::would_abort_on_self_trade(order, crossable) {
+            return Err(err);
+        }
+
+        for limit in self.bids.values_mut().rev().take_while(|limit| limit.price >= price) {
+            if order.is_filled() {
+                break;
+            }
+
+            fills.extend(limit.fill_limit_order(order)?);
+
+            if limit.is_filled() {
+                drained.push(limit.price);
+            }
+        }
+
+        for price in drained {
+            if let Some(limit) = self.bids.remove(&price) {
+                for order in &limit.orders {
+                    self.order_index.remove(&order.id);
+                }
+            }
+        }
+
+        Ok(fills)
     }
 
-    pub fn add_limit_order(&mut self, price: Decimal, order: Order) {
+    fn rest_limit_order(&mut self, price: Decimal, order: Order) {
+        self.order_index.insert(order.id, (order.bid_or_ask, price));
+
         match order.bid_or_ask {
             BidOrAsk::Bid => {
                 match self.bids.get_mut(&price) {
@@ -84,7 +341,7 @@ impl OrderBook {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Limit {
     price: Decimal,
     orders: Vec<Order>,
@@ -98,56 +355,218 @@ impl Limit {
         }
     }
 
-    fn total_volume(&self) -> f64 {
+    fn total_volume(&self) -> Decimal {
         self
         .orders
         .iter()
         .map(|order|order.size)
-        .reduce(|a, b| a+b)
-        .unwrap()
+        .fold(Decimal::ZERO, |a, b| a+b)
+    }
+
+    fn is_filled(&self) -> bool {
+        self.total_volume().is_zero()
     }
 
-    fn fill_order(&mut self, market_order: &mut Order) {
+    fn fill_order(&mut self, market_order: &mut Order) -> Vec<Fill> {
+        let mut fills = Vec::new();
+
         for limit_order in self.orders.iter_mut() {
-            match market_order.size >= limit_order.size {
+            if market_order.is_filled() {
+                break;
+            }
+
+            let traded_size = match market_order.size >= limit_order.size {
                 true => {
                     market_order.size -= limit_order.size;
-                    limit_order.size = 0.0;
+                    let traded_size = limit_order.size;
+                    limit_order.size = Decimal::ZERO;
+                    traded_size
                 }
                 false => {
                     limit_order.size -= market_order.size;
-                    market_order.size = 0.0;
+                    let traded_size = market_order.size;
+                    market_order.size = Decimal::ZERO;
+                    traded_size
                 }
+            };
+
+            if traded_size > Decimal::ZERO {
+                fills.push(Fill {
+                    price: self.price,
+                    size: traded_size,
+                    maker_side: limit_order.bid_or_ask,
+                    taker_side: market_order.bid_or_ask,
+                    maker_owner: limit_order.owner,
+                    taker_owner: market_order.owner,
+                    maker_fee: Decimal::ZERO,
+                    taker_fee: Decimal::ZERO,
+                });
             }
+        }
 
-            if market_order.is_filled() {
+        fills
+    }
+
+    // like `fill_order`, but applies self-trade protection: a resting order owned by
+    // the same participant as the taker is handled per the taker's `self_trade_behavior`
+    // instead of crossing normally
+    fn fill_limit_order(&mut self, taker_order: &mut Order) -> Result<Vec<Fill>, String> {
+        let mut fills = Vec::new();
+
+        for maker_order in self.orders.iter_mut() {
+            if taker_order.is_filled() {
                 break;
             }
+            if maker_order.is_filled() {
+                continue;
+            }
+
+            if maker_order.owner == taker_order.owner {
+                match taker_order.self_trade_behavior {
+                    SelfTradeBehavior::AbortTransaction => {
+                        return Err(format!(
+                            "order from owner {} aborted: would self-trade against its own resting order at price {}",
+                            taker_order.owner, self.price
+                        ));
+                    }
+                    SelfTradeBehavior::CancelProvide => {
+                        maker_order.size = Decimal::ZERO;
+                    }
+                    SelfTradeBehavior::DecrementTake => {
+                        let decremented = taker_order.size.min(maker_order.size);
+                        taker_order.size -= decremented;
+                        maker_order.size -= decremented;
+                    }
+                }
+                continue;
+            }
+
+            let traded_size = match taker_order.size >= maker_order.size {
+                true => {
+                    taker_order.size -= maker_order.size;
+                    let traded_size = maker_order.size;
+                    maker_order.size = Decimal::ZERO;
+                    traded_size
+                }
+                false => {
+                    maker_order.size -= taker_order.size;
+                    let traded_size = taker_order.size;
+                    taker_order.size = Decimal::ZERO;
+                    traded_size
+                }
+            };
+
+            if traded_size > Decimal::ZERO {
+                fills.push(Fill {
+                    price: self.price,
+                    size: traded_size,
+                    maker_side: maker_order.bid_or_ask,
+                    taker_side: taker_order.bid_or_ask,
+                    maker_owner: maker_order.owner,
+                    taker_owner: taker_order.owner,
+                    maker_fee: Decimal::ZERO,
+                    taker_fee: Decimal::ZERO,
+                });
+            }
         }
+
+        Ok(fills)
     }
 
     pub fn add_order(&mut self, order: Order) {
         self.orders.push(order);
     }
+
+    // removes the order matching `id`; returns whether one was found
+    fn remove_order(&mut self, id: OrderId) -> bool {
+        let len_before = self.orders.len();
+        self.orders.retain(|order| order.id != id);
+        self.orders.len() != len_before
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Order {
-    size: f64, 
+    id: OrderId,
+    size: Decimal,
     bid_or_ask: BidOrAsk,
+    owner: OwnerId,
+    self_trade_behavior: SelfTradeBehavior,
+    order_type: OrderType,
 }
 
 impl Order {
-    pub fn new(bid_or_ask: BidOrAsk, size: f64) -> Order {
+    pub fn new(bid_or_ask: BidOrAsk, size: Decimal, owner: OwnerId) -> Order {
         Order{
+            id: NEXT_ORDER_ID.fetch_add(1, Ordering::Relaxed),
             size,
             bid_or_ask,
+            owner,
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            order_type: OrderType::Limit,
         }
     }
 
+    pub fn with_self_trade_behavior(mut self, self_trade_behavior: SelfTradeBehavior) -> Order {
+        self.self_trade_behavior = self_trade_behavior;
+        self
+    }
+
+    pub fn with_order_type(mut self, order_type: OrderType) -> Order {
+        self.order_type = order_type;
+        self
+    }
+
+    pub fn id(&self) -> OrderId {
+        self.id
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
     // for not giving the ownership to the function, &self is given instead of self
     pub fn is_filled(&self) -> bool {
-        self.size == 0.0
+        self.size.is_zero()
+    }
+
+    // the price at which this order should activate, if it is a stop or stop-limit
+    pub fn trigger_price(&self) -> Option<Decimal> {
+        match self.order_type {
+            OrderType::Stop { trigger } => Some(trigger),
+            OrderType::StopLimit { trigger, .. } => Some(trigger),
+            OrderType::Market | OrderType::Limit => None,
+        }
+    }
+
+    // whether `last_price` has crossed this order's trigger: a buy stop activates on
+    // the way up, a sell stop activates on the way down
+    pub fn is_triggered(&self, last_price: Decimal) -> bool {
+        match self.trigger_price() {
+            Some(trigger) => match self.bid_or_ask {
+                BidOrAsk::Bid => last_price >= trigger,
+                BidOrAsk::Ask => last_price <= trigger,
+            },
+            None => false,
+        }
+    }
+
+    // converts a triggered stop into the order it becomes once activated: a plain
+    // `Stop` turns into a `Market` order, a `StopLimit` turns into a `Limit` order
+    // resting at its stored `limit` price (returned alongside so the caller knows
+    // where to rest it; `None` means route the order as a market order instead)
+    pub fn activate(mut self) -> (Order, Option<Decimal>) {
+        match self.order_type {
+            OrderType::Stop { .. } => {
+                self.order_type = OrderType::Market;
+                (self, None)
+            }
+            OrderType::StopLimit { limit, .. } => {
+                self.order_type = OrderType::Limit;
+                (self, Some(limit))
+            }
+            OrderType::Market | OrderType::Limit => (self, None),
+        }
     }
 }
 
@@ -156,15 +575,20 @@ pub mod tests{
     use super::*;
     use rust_decimal_macros::dec;
 
+    // loose tick/lot/min-size so existing test prices and sizes stay valid
+    fn new_orderbook() -> OrderBook {
+        OrderBook::new(dec!(1), dec!(1), dec!(0)).unwrap()
+    }
+
     #[test]
     fn orderbook_fill_market_order_ask() {
-        let mut orderbook = OrderBook::new();
-        orderbook.add_limit_order(dec!(500), Order::new(BidOrAsk::Ask, 10.0));
-        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, 10.0));
-        orderbook.add_limit_order(dec!(200), Order::new(BidOrAsk::Ask, 10.0));
-        orderbook.add_limit_order(dec!(300), Order::new(BidOrAsk::Ask, 10.0));
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(500), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(200), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(300), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
 
-        let mut market_order = Order::new(BidOrAsk::Bid, 10.0);
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(10.0), 1);
         orderbook.fill_market_order(&mut market_order);
 
         let ask_limits = orderbook.ask_limits();
@@ -180,33 +604,253 @@ pub mod tests{
         println!("{:?}", orderbook.ask_limits());
     }
 
+    #[test]
+    fn orderbook_fill_market_order_returns_fills() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(10.0), 1);
+        let fills = orderbook.fill_market_order(&mut market_order);
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, dec!(100));
+        assert_eq!(fills[0].size, dec!(10.0));
+        assert_eq!(fills[0].maker_side, BidOrAsk::Ask);
+        assert_eq!(fills[0].taker_side, BidOrAsk::Bid);
+    }
+
+    #[test]
+    fn orderbook_limit_order_crosses_book() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+
+        let fills = orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Bid, dec!(15.0), 2)).unwrap();
+
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].price, dec!(100));
+        assert_eq!(fills[0].size, dec!(10.0));
+        assert_eq!(fills[1].price, dec!(105));
+        assert_eq!(fills[1].size, dec!(5.0));
+        assert_eq!(orderbook.asks.get(&dec!(100)), None);
+        assert_eq!(orderbook.asks.get(&dec!(105)).unwrap().total_volume(), dec!(5.0));
+    }
+
+    #[test]
+    fn orderbook_limit_order_does_not_self_cross() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(110), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+
+        let fills = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(10.0), 1)).unwrap();
+
+        assert_eq!(fills.len(), 0);
+        assert_eq!(orderbook.bids.get(&dec!(100)).unwrap().total_volume(), dec!(10.0));
+        assert_eq!(orderbook.asks.get(&dec!(110)).unwrap().total_volume(), dec!(10.0));
+    }
+
+    #[test]
+    fn orderbook_self_trade_cancel_provide_skips_own_resting_order() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0), 2)).unwrap();
+
+        let taker = Order::new(BidOrAsk::Bid, dec!(10.0), 1).with_self_trade_behavior(SelfTradeBehavior::CancelProvide);
+        let fills = orderbook.add_limit_order(dec!(100), taker).unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].size, dec!(10.0));
+        assert_eq!(orderbook.asks.get(&dec!(100)), None);
+    }
+
+    #[test]
+    fn orderbook_self_trade_decrement_take_cancels_the_smaller_order_without_a_fill() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(4.0), 1)).unwrap();
+
+        let taker = Order::new(BidOrAsk::Bid, dec!(10.0), 1).with_self_trade_behavior(SelfTradeBehavior::DecrementTake);
+        let fills = orderbook.add_limit_order(dec!(100), taker).unwrap();
+
+        assert_eq!(fills.len(), 0);
+        assert_eq!(orderbook.asks.get(&dec!(100)), None);
+        assert_eq!(orderbook.bids.get(&dec!(100)).unwrap().total_volume(), dec!(6.0));
+    }
+
+    #[test]
+    fn orderbook_self_trade_abort_transaction_rejects_the_order() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0), 1)).unwrap();
+
+        let taker = Order::new(BidOrAsk::Bid, dec!(10.0), 1).with_self_trade_behavior(SelfTradeBehavior::AbortTransaction);
+        let result = orderbook.add_limit_order(dec!(100), taker);
+
+        assert!(result.is_err());
+        assert_eq!(orderbook.asks.get(&dec!(100)).unwrap().total_volume(), dec!(10.0));
+        assert_eq!(orderbook.bids.get(&dec!(100)), None);
+    }
+
+    #[test]
+    fn orderbook_self_trade_abort_transaction_does_not_reject_when_other_liquidity_fills_first() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(5.0), 2)).unwrap();
+        orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Ask, dec!(5.0), 1)).unwrap();
+
+        // fully satisfiable by owner 2 at 100 before ever reaching owner 1's own order at 105
+        let taker = Order::new(BidOrAsk::Bid, dec!(5.0), 1).with_self_trade_behavior(SelfTradeBehavior::AbortTransaction);
+        let fills = orderbook.add_limit_order(dec!(105), taker).unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, dec!(100));
+        assert_eq!(orderbook.asks.get(&dec!(100)), None);
+        assert_eq!(orderbook.asks.get(&dec!(105)).unwrap().total_volume(), dec!(5.0));
+    }
+
+    #[test]
+    fn orderbook_best_bid_best_ask_and_spread() {
+        let mut orderbook = new_orderbook();
+        assert_eq!(orderbook.best_bid(), None);
+        assert_eq!(orderbook.best_ask(), None);
+        assert_eq!(orderbook.spread(), None);
+
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(95), Order::new(BidOrAsk::Bid, dec!(10.0), 1)).unwrap();
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(10.0), 2)).unwrap();
+        orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Ask, dec!(10.0), 2)).unwrap();
+
+        assert_eq!(orderbook.best_bid(), Some(dec!(99)));
+        assert_eq!(orderbook.best_ask(), Some(dec!(101)));
+        assert_eq!(orderbook.spread(), Some(dec!(2)));
+    }
+
+    #[test]
+    fn orderbook_cancel_order_removes_resting_order() {
+        let mut orderbook = new_orderbook();
+        let order = Order::new(BidOrAsk::Bid, dec!(10.0), 1);
+        let id = order.id();
+        orderbook.add_limit_order(dec!(100), order).unwrap();
+
+        assert_eq!(orderbook.cancel_order(id), true);
+        assert_eq!(orderbook.bids.get(&dec!(100)), None);
+        assert_eq!(orderbook.cancel_order(id), false);
+    }
+
+    #[test]
+    fn orderbook_cancel_order_drops_a_limit_left_with_only_filled_ghost_orders() {
+        let mut orderbook = new_orderbook();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(5.0), 1)).unwrap();
+        let live = Order::new(BidOrAsk::Ask, dec!(5.0), 2);
+        let live_id = live.id();
+        orderbook.add_limit_order(dec!(100), live).unwrap();
+
+        // fully fills owner 1's resting order, leaving it as a zero-size ghost in the vec
+        orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(5.0), 3));
+        assert_eq!(orderbook.asks.get(&dec!(100)).unwrap().total_volume(), dec!(5.0));
+
+        assert_eq!(orderbook.cancel_order(live_id), true);
+        assert_eq!(orderbook.asks.get(&dec!(100)), None);
+        assert_eq!(orderbook.best_ask(), None);
+    }
+
+    #[test]
+    fn orderbook_cancel_all_orders_clears_one_side() {
+        let mut orderbook = new_orderbook();
+        let bid = Order::new(BidOrAsk::Bid, dec!(10.0), 1);
+        let bid_id = bid.id();
+        orderbook.add_limit_order(dec!(100), bid).unwrap();
+        orderbook.add_limit_order(dec!(200), Order::new(BidOrAsk::Ask, dec!(5.0), 1)).unwrap();
+
+        orderbook.cancel_all_orders(BidOrAsk::Bid);
+
+        assert_eq!(orderbook.bids.len(), 0);
+        assert_eq!(orderbook.asks.len(), 1);
+        assert_eq!(orderbook.cancel_order(bid_id), false);
+    }
+
+    #[test]
+    fn orderbook_add_limit_order_rejects_invalid_tick_size() {
+        let mut orderbook = OrderBook::new(dec!(0.5), dec!(1), dec!(0)).unwrap();
+
+        let result = orderbook.add_limit_order(dec!(100.25), Order::new(BidOrAsk::Bid, dec!(10.0), 1));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn orderbook_add_limit_order_rejects_dust_below_min_size() {
+        let mut orderbook = OrderBook::new(dec!(1), dec!(0.1), dec!(1)).unwrap();
+
+        let result = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(0.5), 1));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn orderbook_new_rejects_a_zero_tick_size() {
+        assert!(OrderBook::new(dec!(0), dec!(1), dec!(0)).is_err());
+    }
+
+    #[test]
+    fn orderbook_new_rejects_a_zero_lot_size() {
+        assert!(OrderBook::new(dec!(1), dec!(0), dec!(0)).is_err());
+    }
+
+    #[test]
+    fn order_buy_stop_triggers_once_last_price_rises_to_trigger() {
+        let stop = Order::new(BidOrAsk::Bid, dec!(10.0), 1).with_order_type(OrderType::Stop { trigger: dec!(100) });
+
+        assert_eq!(stop.is_triggered(dec!(99)), false);
+        assert_eq!(stop.is_triggered(dec!(100)), true);
+        assert_eq!(stop.is_triggered(dec!(101)), true);
+    }
+
+    #[test]
+    fn order_sell_stop_triggers_once_last_price_falls_to_trigger() {
+        let stop = Order::new(BidOrAsk::Ask, dec!(10.0), 1).with_order_type(OrderType::Stop { trigger: dec!(100) });
+
+        assert_eq!(stop.is_triggered(dec!(101)), false);
+        assert_eq!(stop.is_triggered(dec!(100)), true);
+        assert_eq!(stop.is_triggered(dec!(99)), true);
+    }
+
+    #[test]
+    fn order_activate_converts_stop_to_market_and_stop_limit_to_limit() {
+        let stop = Order::new(BidOrAsk::Bid, dec!(10.0), 1).with_order_type(OrderType::Stop { trigger: dec!(100) });
+        let (activated, limit_price) = stop.activate();
+        assert_eq!(activated.order_type(), OrderType::Market);
+        assert_eq!(limit_price, None);
+
+        let stop_limit = Order::new(BidOrAsk::Bid, dec!(10.0), 1).with_order_type(OrderType::StopLimit { trigger: dec!(100), limit: dec!(101) });
+        let (activated, limit_price) = stop_limit.activate();
+        assert_eq!(activated.order_type(), OrderType::Limit);
+        assert_eq!(limit_price, Some(dec!(101)));
+    }
+
     #[test]
     fn limit_total_volume() {
         let price = dec!(10000);
         let mut limit = Limit::new(price);
-        let buy_limit_order_a = Order::new(BidOrAsk::Bid, 100.0); 
-        let buy_limit_order_b = Order::new(BidOrAsk::Bid, 100.0); 
+        let buy_limit_order_a = Order::new(BidOrAsk::Bid, dec!(100.0), 1); 
+        let buy_limit_order_b = Order::new(BidOrAsk::Bid, dec!(100.0), 1); 
         limit.add_order(buy_limit_order_a);
         limit.add_order(buy_limit_order_b);
 
-        assert_eq!(limit.total_volume(), 200.0);
+        assert_eq!(limit.total_volume(), dec!(200.0));
     }
 
     #[test]
     fn limit_order_multi_fill() {
         let price = dec!(10000);
         let mut limit = Limit::new(price);
-        let buy_limit_order_a = Order::new(BidOrAsk::Bid, 100.0); 
-        let buy_limit_order_b = Order::new(BidOrAsk::Bid, 100.0); 
+        let buy_limit_order_a = Order::new(BidOrAsk::Bid, dec!(100.0), 1); 
+        let buy_limit_order_b = Order::new(BidOrAsk::Bid, dec!(100.0), 1); 
         limit.add_order(buy_limit_order_a);
         limit.add_order(buy_limit_order_b);
-        let mut sell_market_order = Order::new(BidOrAsk::Ask, 199.0); 
+        let mut sell_market_order = Order::new(BidOrAsk::Ask, dec!(199.0), 1); 
         limit.fill_order(&mut sell_market_order);
         
         assert_eq!(sell_market_order.is_filled(), true);
         assert_eq!(limit.orders.get(0).unwrap().is_filled(), true);
         assert_eq!(limit.orders.get(1).unwrap().is_filled(), false);
-        assert_eq!(limit.orders.get(1).unwrap().size, 1.0);
+        assert_eq!(limit.orders.get(1).unwrap().size, dec!(1.0));
 
         println!("{:?}", limit);
     }
@@ -215,13 +859,13 @@ pub mod tests{
     fn limit_order_single_fill() {
         let price = dec!(10000);
         let mut limit = Limit::new(price);
-        let buy_limit_order = Order::new(BidOrAsk::Bid, 100.0); 
+        let buy_limit_order = Order::new(BidOrAsk::Bid, dec!(100.0), 1); 
         limit.add_order(buy_limit_order);
-        let mut sell_market_order = Order::new(BidOrAsk::Ask, 99.0); 
+        let mut sell_market_order = Order::new(BidOrAsk::Ask, dec!(99.0), 1); 
         limit.fill_order(&mut sell_market_order);
         
         assert_eq!(sell_market_order.is_filled(), true);
-        assert_eq!(limit.orders.get(0).unwrap().size, 1.0);
+        assert_eq!(limit.orders.get(0).unwrap().size, dec!(1.0));
     }
 
     